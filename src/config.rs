@@ -1,11 +1,18 @@
 //! Configuration related structures
+use crate::container_logging::{ContainerLogging, Driver};
 use anyhow::{bail, Context, Result};
-use clap::{crate_version, AppSettings, Parser};
+use clap::{
+    crate_version, AppSettings, ArgMatches, CommandFactory, FromArgMatches, Parser, ValueSource,
+};
 use derive_builder::Builder;
 use getset::{CopyGetters, Getters, Setters};
 use log::{debug, LevelFilter};
 use serde::{Deserialize, Serialize};
-use std::{env, path::PathBuf};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
 
 macro_rules! prefix {
     () => {
@@ -57,6 +64,7 @@ pub struct Config {
 
     #[get = "pub"]
     #[clap(
+        default_value(""),
         env(concat!(prefix!(), "CID")),
         long("cid"),
         short('c'),
@@ -175,7 +183,7 @@ pub struct Config {
         env(concat!(prefix!(), "LOG_PATH")),
         long("log-path"),
         multiple_occurrences(true),
-        required(true),
+        required_unless_present("config"),
         short('l'),
         value_name("[DRIVER:]PATH")
     )]
@@ -193,6 +201,16 @@ pub struct Config {
     /// Maximum size of log file.
     log_size_max: i64,
 
+    #[get_copy = "pub"]
+    #[clap(
+        default_value("-1"),
+        env(concat!(prefix!(), "LOG_GLOBAL_SIZE_MAX")),
+        long("log-global-size-max"),
+        value_name("BYTE")
+    )]
+    /// Maximum size of total log output across all drivers, combined.
+    log_global_size_max: i64,
+
     #[get = "pub"]
     #[clap(
         env(concat!(prefix!(), "LOG_TAG")),
@@ -287,6 +305,7 @@ pub struct Config {
 
     #[get = "pub"]
     #[clap(
+        default_value(""),
         env(concat!(prefix!(), "RUNTIME")),
         long("runtime"),
         short('r'),
@@ -413,17 +432,184 @@ pub struct Config {
     )]
     /// Plugins to use for managing the seccomp notifications.
     seccomp_notify_plugins: Option<String>,
+
+    #[get = "pub"]
+    #[clap(
+        env(concat!(prefix!(), "CONFIG")),
+        long("config"),
+        value_name("PATH")
+    )]
+    /// Path to a TOML or YAML configuration file. Values from the file are merged with the
+    /// defaults, with CLI flags and environment variables taking precedence over it.
+    config: Option<PathBuf>,
 }
 
 impl Default for Config {
     fn default() -> Self {
-        Self::parse()
+        Self::load().unwrap_or_else(|e| {
+            eprintln!("Error: {:?}", e);
+            exit(1);
+        })
     }
 }
 
+/// Mirrors every field of `Config` as an `Option` so that a `--config` file only needs to
+/// specify the subset of values it wants to override.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PartialConfig {
+    log_level: Option<LevelFilter>,
+    api_version: Option<u8>,
+    bundle: Option<PathBuf>,
+    cid: Option<String>,
+    conmon_pidfile: Option<PathBuf>,
+    container_pidfile: Option<PathBuf>,
+    cuuid: Option<String>,
+    exec: Option<bool>,
+    exec_attach: Option<bool>,
+    exec_process_spec: Option<PathBuf>,
+    exit_command: Option<PathBuf>,
+    exit_command_arg: Option<Vec<String>>,
+    exit_delay: Option<u32>,
+    exit_dir: Option<PathBuf>,
+    leave_stdin_open: Option<bool>,
+    log_path: Option<Vec<String>>,
+    log_size_max: Option<i64>,
+    log_global_size_max: Option<i64>,
+    log_tag: Option<String>,
+    name: Option<String>,
+    no_new_keyring: Option<bool>,
+    no_pivot: Option<bool>,
+    no_sync_log: Option<bool>,
+    persist_dir: Option<PathBuf>,
+    pidfile: Option<PathBuf>,
+    replace_listen_pid: Option<bool>,
+    restore: Option<PathBuf>,
+    restore_arg: Option<Vec<String>>,
+    runtime: Option<PathBuf>,
+    runtime_arg: Option<Vec<String>>,
+    runtime_opt: Option<Vec<String>>,
+    sdnotify_socket: Option<PathBuf>,
+    socket_dir_path: Option<PathBuf>,
+    stdin: Option<bool>,
+    sync: Option<bool>,
+    syslog: Option<bool>,
+    systemd_cgroup: Option<bool>,
+    terminal: Option<bool>,
+    timeout: Option<u32>,
+    full_attach: Option<bool>,
+    seccomp_notify_socket: Option<PathBuf>,
+    seccomp_notify_plugins: Option<String>,
+}
+
 impl Config {
+    /// Parse the configuration from CLI arguments and the environment, then merge in an optional
+    /// `--config`/`CONMON_CONFIG` file. Explicit CLI flags and environment variables take
+    /// precedence over the file, which takes precedence over the built-in defaults.
+    fn load() -> Result<Self> {
+        let matches = Self::command().get_matches();
+        let mut config = Self::from_arg_matches(&matches).context("parse arguments")?;
+
+        if let Some(path) = config.config().clone() {
+            let partial = Self::parse_config_file(&path)
+                .with_context(|| format!("parse config file {}", path.display()))?;
+            config.merge_file(partial, &matches);
+        }
+
+        Ok(config)
+    }
+
+    /// Parse a TOML or YAML configuration file into a `PartialConfig`, selecting the format by
+    /// the file extension (`.yaml`/`.yml`) and falling back to TOML otherwise.
+    fn parse_config_file(path: &Path) -> Result<PartialConfig> {
+        let contents = fs::read_to_string(path).context("read config file")?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml") | Some("yml") => {
+                serde_yaml::from_str(&contents).context("parse YAML config file")
+            }
+            _ => toml::from_str(&contents).context("parse TOML config file"),
+        }
+    }
+
+    /// Overlay `partial` onto `self`, skipping any field the user already set explicitly via a
+    /// CLI flag or an environment variable.
+    fn merge_file(&mut self, partial: PartialConfig, matches: &ArgMatches) {
+        macro_rules! merge {
+            ($field:ident) => {
+                // clap derives kebab-case argument ids from the field names.
+                if !matches!(
+                    matches.value_source(stringify!($field).replace('_', "-").as_str()),
+                    Some(ValueSource::CommandLine) | Some(ValueSource::EnvVariable)
+                ) {
+                    if let Some(value) = partial.$field {
+                        self.$field = value;
+                    }
+                }
+            };
+        }
+
+        merge!(log_level);
+        merge!(api_version);
+        merge!(bundle);
+        merge!(cid);
+        merge!(conmon_pidfile);
+        merge!(container_pidfile);
+        merge!(cuuid);
+        merge!(exec);
+        merge!(exec_attach);
+        merge!(exec_process_spec);
+        merge!(exit_command);
+        merge!(exit_command_arg);
+        merge!(exit_delay);
+        merge!(exit_dir);
+        merge!(leave_stdin_open);
+        merge!(log_path);
+        merge!(log_size_max);
+        merge!(log_global_size_max);
+        merge!(log_tag);
+        merge!(name);
+        merge!(no_new_keyring);
+        merge!(no_pivot);
+        merge!(no_sync_log);
+        merge!(persist_dir);
+        merge!(pidfile);
+        merge!(replace_listen_pid);
+        merge!(restore);
+        merge!(restore_arg);
+        merge!(runtime);
+        merge!(runtime_arg);
+        merge!(runtime_opt);
+        merge!(sdnotify_socket);
+        merge!(socket_dir_path);
+        merge!(stdin);
+        merge!(sync);
+        merge!(syslog);
+        merge!(systemd_cgroup);
+        merge!(terminal);
+        merge!(timeout);
+        merge!(full_attach);
+        merge!(seccomp_notify_socket);
+        merge!(seccomp_notify_plugins);
+    }
+
     /// Validate the configuration integrity.
     pub fn validate(&mut self) -> Result<()> {
+        // `--cid` and `--runtime` default to empty and `--log-path` is only
+        // `required_unless_present("config")` on the CLI, so that a `--config` file can supply
+        // them instead. Once the file has been merged in, re-check that one source or the other
+        // actually provided them.
+        if self.cid().is_empty() {
+            bail!("container ID not provided, use --cid or the config file")
+        }
+
+        if self.runtime().as_os_str().is_empty() {
+            bail!("runtime path not provided, use --runtime or the config file")
+        }
+
+        if self.log_path().is_empty() {
+            bail!("log path not provided, use --log-path or the config file")
+        }
+
         if self.api_version() < 1 && self.exec_attach() {
             bail!("attach can only be specified for a non-legacy exec session")
         }
@@ -459,4 +645,139 @@ impl Config {
 
         Ok(())
     }
+
+    /// Parse the configured `--log-path` entries into their typed `Driver` values, so that
+    /// callers can pick e.g. the `k8s-file` format without re-parsing the raw strings themselves.
+    pub fn log_drivers(&self) -> Result<Vec<Driver>> {
+        self.log_path
+            .iter()
+            .map(|path| ContainerLogging::parse_log_path(path))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_config() -> Config {
+        Config::try_parse_from(["conmon", "--log-path", "/unused/log"]).unwrap()
+    }
+
+    #[test]
+    fn log_drivers_parses_every_configured_log_path() -> Result<()> {
+        let mut config = new_config();
+        config.log_path = vec!["k8s-file:/some/path".into(), "journald".into()];
+
+        let drivers = config.log_drivers()?;
+
+        assert_eq!(
+            drivers,
+            vec![
+                Driver::K8sFile("/some/path".into()),
+                Driver::Journald(Default::default()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn log_drivers_rejects_an_invalid_entry() {
+        let mut config = new_config();
+        config.log_path = vec!["wrong:/some/path".into()];
+
+        assert!(config.log_drivers().is_err());
+    }
+
+    #[test]
+    fn merge_file_lets_cli_flags_beat_the_config_file() -> Result<()> {
+        let matches = Config::command().get_matches_from(vec![
+            "conmon",
+            "--cid",
+            "cli-cid",
+            "--runtime",
+            "/cli/runtime",
+            "--log-path",
+            "/cli/log",
+        ]);
+        let mut config = Config::from_arg_matches(&matches).context("parse cli args")?;
+
+        let partial = PartialConfig {
+            cid: Some("file-cid".into()),
+            runtime: Some("/file/runtime".into()),
+            ..Default::default()
+        };
+        config.merge_file(partial, &matches);
+
+        assert_eq!(config.cid(), "cli-cid");
+        assert_eq!(config.runtime(), &PathBuf::from("/cli/runtime"));
+        Ok(())
+    }
+
+    #[test]
+    fn merge_file_lets_the_config_file_beat_the_default() -> Result<()> {
+        let matches = Config::command().get_matches_from(vec![
+            "conmon",
+            "--cid",
+            "cli-cid",
+            "--runtime",
+            "/cli/runtime",
+            "--log-path",
+            "/cli/log",
+        ]);
+        let mut config = Config::from_arg_matches(&matches).context("parse cli args")?;
+        assert_eq!(config.log_tag(), &None);
+
+        let partial = PartialConfig {
+            log_tag: Some("file-tag".into()),
+            ..Default::default()
+        };
+        config.merge_file(partial, &matches);
+
+        assert_eq!(config.log_tag(), &Some("file-tag".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn validate_requires_cid_runtime_and_log_path_after_merge() {
+        let mut config = new_config();
+        config.runtime = PathBuf::from(".");
+
+        let err = config.validate().expect_err("cid still missing");
+        assert!(err.to_string().contains("container ID"));
+
+        config.cid = "cid".into();
+        config.runtime = PathBuf::new();
+        let err = config.validate().expect_err("runtime still missing");
+        assert!(err.to_string().contains("runtime path"));
+
+        config.runtime = PathBuf::from(".");
+        config.log_path = vec![];
+        let err = config.validate().expect_err("log path still missing");
+        assert!(err.to_string().contains("log path"));
+    }
+
+    #[test]
+    fn from_arg_matches_succeeds_with_only_the_config_flag() -> Result<()> {
+        let path = env::temp_dir().join("conmon-test-config-only.toml");
+        fs::write(
+            &path,
+            "cid = \"file-cid\"\nruntime = \"/file/runtime\"\nlog-path = [\"/file/log\"]\n",
+        )
+        .context("write test config file")?;
+
+        let matches = Config::command()
+            .try_get_matches_from(vec!["conmon", "--config", path.to_str().unwrap()])
+            .context("parse cli args with only --config")?;
+        let mut config = Config::from_arg_matches(&matches).context("parse arguments")?;
+
+        let partial = Config::parse_config_file(&path).context("parse config file")?;
+        config.merge_file(partial, &matches);
+        fs::remove_file(&path).ok();
+
+        assert_eq!(config.cid(), "file-cid");
+        assert_eq!(config.runtime(), &PathBuf::from("/file/runtime"));
+        assert_eq!(config.log_path(), &vec!["/file/log".to_string()]);
+        Ok(())
+    }
 }
@@ -1,15 +1,22 @@
 //! Container logging related implementations
 
 use anyhow::{bail, Context, Result};
+use chrono::{SecondsFormat, Utc};
 use getset::{Getters, Setters};
 use log::{debug, warn};
 use std::{
+    ffi::CString,
     fs::{File, OpenOptions},
+    io::Write,
     path::PathBuf,
     str::FromStr,
 };
 use strum::{AsRefStr, EnumString};
 
+/// The maximum amount of bytes to buffer for a single stream before a line is forcibly flushed as
+/// a partial record, mirroring the size of a single read buffer.
+const MAX_PARTIAL_LINE_LEN: usize = 8 * 1024;
+
 #[derive(Debug, Getters)]
 /// ContainerLogging is the structure used for everything around logging.
 pub struct ContainerLogging {
@@ -17,9 +24,64 @@ pub struct ContainerLogging {
     /// Selected log drivers.
     drivers: Vec<Driver>,
 
-    #[get]
-    /// Log files if required.
-    files: Vec<File>,
+    /// Open log files and their rotation bookkeeping, if required.
+    files: Vec<LogFile>,
+
+    /// Buffered bytes not yet terminated by a newline, tracked per stream.
+    stdout_partial: Vec<u8>,
+
+    /// Buffered bytes not yet terminated by a newline, tracked per stream.
+    stderr_partial: Vec<u8>,
+
+    /// Maximum size in bytes of a single log file before it gets rotated, or `-1` to disable.
+    log_size_max: i64,
+
+    /// Maximum cumulative bytes written across all drivers before output is dropped, or `-1` to
+    /// disable.
+    log_global_size_max: i64,
+
+    /// Running total of bytes written across all drivers for this container.
+    global_bytes_written: i64,
+
+    /// Tag prefixed onto every message sent to the `syslog` driver. `openlog(3)` is intentionally
+    /// never called here: it is process-global state, and conmon's own diagnostic logging (see
+    /// `Conmon::init_logging`) may already have it open under a different identity. Carrying the
+    /// tag in the message instead means the two never fight over the same global.
+    syslog_tag: Option<String>,
+}
+
+#[derive(Debug)]
+/// A single k8s-file log destination together with its rotation state.
+struct LogFile {
+    /// Path the file was opened from, needed to reopen it on rotation.
+    path: PathBuf,
+
+    /// The currently open file handle.
+    file: File,
+
+    /// Current size of `file` in bytes: its length when opened plus everything written since,
+    /// reset to zero on rotation.
+    bytes_written: i64,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+/// The container output stream a chunk of log data originated from.
+pub enum Stream {
+    /// Standard output of the container.
+    Stdout,
+
+    /// Standard error of the container.
+    Stderr,
+}
+
+impl Stream {
+    /// The CRI log format name of this stream.
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+        }
+    }
 }
 
 #[derive(AsRefStr, Clone, Debug, Eq, EnumString, PartialEq)]
@@ -32,6 +94,9 @@ pub enum Driver {
     /// Journald based logging.
     Journald(ContainerFields),
 
+    /// Syslog based logging.
+    Syslog,
+
     /// No logging.
     Off,
 
@@ -55,23 +120,40 @@ pub struct ContainerFields {
 
     #[getset(get, set)]
     name: Option<String>,
+
+    #[getset(get, set)]
+    syslog_identifier: Option<String>,
+}
+
+#[repr(C)]
+/// Mirrors `struct iovec` from `<sys/uio.h>`, as expected by `sd_journal_sendv`.
+struct Iovec {
+    iov_base: *const u8,
+    iov_len: libc::size_t,
+}
+
+extern "C" {
+    /// <https://www.freedesktop.org/software/systemd/man/sd_journal_sendv.html>
+    fn sd_journal_sendv(iov: *const Iovec, n: libc::c_int) -> libc::c_int;
 }
 
 impl ContainerLogging {
-    /// Create a new container logging instance.
+    /// Create a new container logging instance from the already-parsed `log_drivers`, as
+    /// returned by `Config::log_drivers`.
     pub fn new<T: AsRef<str>>(
-        log_paths: &[String],
+        log_drivers: Vec<Driver>,
         cuuid: Option<T>,
         name: Option<T>,
         tag: Option<T>,
+        log_size_max: i64,
+        log_global_size_max: i64,
     ) -> Result<Self> {
         debug!("Configuring container logging");
 
         let mut drivers: Vec<Driver> = vec![];
-        let mut files: Vec<File> = vec![];
+        let mut files: Vec<LogFile> = vec![];
 
-        for log_path in log_paths {
-            let mut driver = Self::parse_log_path(log_path)?;
+        for mut driver in log_drivers {
             match driver {
                 Driver::Off | Driver::Null | Driver::None => continue,
                 Driver::K8sFile(ref path) => {
@@ -79,14 +161,18 @@ impl ContainerLogging {
                         warn!("Ignoring k8s-file log tag because of missing support");
                     }
 
-                    files.push(
-                        OpenOptions::new()
-                            .append(true)
-                            .create(true)
-                            .write(true)
-                            .open(path)
-                            .context("open log file path")?,
-                    );
+                    let file = OpenOptions::new()
+                        .append(true)
+                        .create(true)
+                        .write(true)
+                        .open(path)
+                        .context("open log file path")?;
+                    let bytes_written = file.metadata().context("read log file metadata")?.len();
+                    files.push(LogFile {
+                        path: path.clone(),
+                        file,
+                        bytes_written: bytes_written as i64,
+                    });
                 }
                 Driver::Journald(ref mut fields) => {
                     const TRUNC_ID_LEN: usize = 12;
@@ -106,12 +192,204 @@ impl ContainerLogging {
                         name.as_ref()
                             .map(|x| format!("CONTAINER_NAME={}", x.as_ref())),
                     );
+                    fields.set_syslog_identifier(
+                        tag.as_ref()
+                            .map(|x| format!("SYSLOG_IDENTIFIER={}", x.as_ref())),
+                    );
                 }
+                Driver::Syslog => {}
             }
             drivers.push(driver);
         }
 
-        Ok(Self { drivers, files })
+        let syslog_tag = if drivers.iter().any(|d| *d == Driver::Syslog) {
+            Some(
+                tag.as_ref()
+                    .map(|x| x.as_ref().to_string())
+                    .unwrap_or_else(|| "conmon".into()),
+            )
+        } else {
+            None
+        };
+
+        Ok(Self {
+            drivers,
+            files,
+            stdout_partial: vec![],
+            stderr_partial: vec![],
+            log_size_max,
+            log_global_size_max,
+            global_bytes_written: 0,
+            syslog_tag,
+        })
+    }
+
+    /// Write a chunk of bytes read from the provided stream to every configured log driver,
+    /// formatting it as CRI/k8s-file log lines for the file-backed ones.
+    ///
+    /// Incomplete lines are buffered per stream until their newline arrives and are then written
+    /// as a single `F` (full) record. Only a line that reaches `MAX_PARTIAL_LINE_LEN` bytes
+    /// without a newline is flushed early as a `P` (partial) record, with the chunk that later
+    /// carries the newline tagged as `F`.
+    pub fn write(&mut self, stream: Stream, bytes: &[u8]) -> Result<()> {
+        for (content, full) in self.split_lines(stream, bytes) {
+            self.write_line(stream, &content, full)?;
+        }
+        Ok(())
+    }
+
+    /// Split `bytes` into CRI log records, buffering any trailing partial line in the per-stream
+    /// state so it can be completed by a later call.
+    fn split_lines(&mut self, stream: Stream, bytes: &[u8]) -> Vec<(Vec<u8>, bool)> {
+        let partial = match stream {
+            Stream::Stdout => &mut self.stdout_partial,
+            Stream::Stderr => &mut self.stderr_partial,
+        };
+        partial.extend_from_slice(bytes);
+
+        let mut records = vec![];
+        while let Some(pos) = partial.iter().position(|&b| b == b'\n') {
+            records.push((partial.drain(..=pos).collect::<Vec<u8>>(), true));
+        }
+
+        // A line that never terminates is still flushed once it reaches the read buffer size, to
+        // bound memory usage and keep consumers from stalling on a single giant record.
+        if partial.len() >= MAX_PARTIAL_LINE_LEN {
+            records.push((partial.drain(..).collect(), false));
+        }
+
+        records
+    }
+
+    /// Write a single already-split CRI log record to every open file-backed driver, and submit
+    /// it to the journal or the system logger for every configured journald or syslog driver.
+    fn write_line(&mut self, stream: Stream, content: &[u8], full: bool) -> Result<()> {
+        if self.log_global_size_max >= 0 && self.global_bytes_written >= self.log_global_size_max
+        {
+            return Ok(());
+        }
+
+        let mut line = format!(
+            "{} {} {} ",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true),
+            stream.as_str(),
+            if full { "F" } else { "P" },
+        )
+        .into_bytes();
+        line.extend_from_slice(content);
+        if !full {
+            line.push(b'\n');
+        }
+
+        let log_size_max = self.log_size_max;
+        for log_file in &mut self.files {
+            if log_size_max >= 0
+                && log_file.bytes_written > 0
+                && log_file.bytes_written + line.len() as i64 > log_size_max
+            {
+                Self::rotate(log_file)?;
+            }
+            log_file.file.write_all(&line).context("write log line")?;
+            log_file.bytes_written += line.len() as i64;
+        }
+
+        for driver in &self.drivers {
+            match driver {
+                Driver::Journald(fields) => Self::send_to_journal(fields, stream, content)?,
+                Driver::Syslog => Self::send_to_syslog(self.syslog_tag.as_deref(), stream, content),
+                _ => {}
+            }
+        }
+
+        self.global_bytes_written += line.len() as i64;
+        Ok(())
+    }
+
+    /// Flush and truncate a rotated log file in place, resetting its byte counter.
+    fn rotate(log_file: &mut LogFile) -> Result<()> {
+        debug!("Rotating log file {}", log_file.path.display());
+        log_file.file.flush().context("flush log file")?;
+        log_file.file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&log_file.path)
+            .context("reopen log file for rotation")?;
+        log_file.bytes_written = 0;
+        Ok(())
+    }
+
+    /// Submit a single log record to the systemd journal via `sd_journal_sendv`, attaching the
+    /// `CONTAINER_*` fields assembled for this container.
+    fn send_to_journal(fields: &ContainerFields, stream: Stream, content: &[u8]) -> Result<()> {
+        let message = content.strip_suffix(b"\n").unwrap_or(content);
+        let priority = match stream {
+            Stream::Stdout => libc::LOG_INFO,
+            Stream::Stderr => libc::LOG_ERR,
+        };
+
+        let mut buffers = vec![
+            [b"MESSAGE=", message].concat(),
+            format!("PRIORITY={}", priority).into_bytes(),
+            fields.id().clone().into_bytes(),
+            fields.id_full().clone().into_bytes(),
+        ];
+        if let Some(tag) = fields.tag() {
+            buffers.push(tag.clone().into_bytes());
+        }
+        if let Some(name) = fields.name() {
+            buffers.push(name.clone().into_bytes());
+        }
+        if let Some(syslog_identifier) = fields.syslog_identifier() {
+            buffers.push(syslog_identifier.clone().into_bytes());
+        }
+
+        let iovecs: Vec<Iovec> = buffers
+            .iter()
+            .map(|b| Iovec {
+                iov_base: b.as_ptr(),
+                iov_len: b.len(),
+            })
+            .collect();
+
+        let ret = unsafe { sd_journal_sendv(iovecs.as_ptr(), iovecs.len() as libc::c_int) };
+        if ret != 0 {
+            bail!("sd_journal_sendv failed with error code {}", ret)
+        }
+        Ok(())
+    }
+
+    /// Submit a single log record to the system logger via `syslog(3)`, mapping the stream to a
+    /// priority so container stderr surfaces at a higher severity than stdout.
+    ///
+    /// `tag` is prefixed onto the message rather than passed to `openlog(3)`, since `openlog` sets
+    /// process-global state that conmon's own diagnostic logging may already be using under a
+    /// different identity.
+    fn send_to_syslog(tag: Option<&str>, stream: Stream, content: &[u8]) {
+        let message = content.strip_suffix(b"\n").unwrap_or(content);
+        let priority = match stream {
+            Stream::Stdout => libc::LOG_INFO,
+            Stream::Stderr => libc::LOG_ERR,
+        };
+
+        let mut buf = Vec::new();
+        if let Some(tag) = tag {
+            buf.extend_from_slice(tag.as_bytes());
+            buf.extend_from_slice(b": ");
+        }
+        buf.extend_from_slice(message);
+
+        let c_message = match CString::new(buf) {
+            Ok(m) => m,
+            Err(_) => CString::new("<log line containing a NUL byte>").unwrap(),
+        };
+        unsafe {
+            libc::syslog(
+                priority,
+                b"%s\0".as_ptr() as *const libc::c_char,
+                c_message.as_ptr(),
+            )
+        };
     }
 
     /// truncate a string slice to its maximums provided characters.
@@ -130,7 +408,7 @@ impl ContainerLogging {
     /// in the case the log driver is 'journald', the <PATH_NAME> is ignored.
     //
     // Errors if <DRIVER_NAME> isn't a variant of `Driver`.
-    fn parse_log_path(log_path: &str) -> Result<Driver> {
+    pub(crate) fn parse_log_path(log_path: &str) -> Result<Driver> {
         let splitted = log_path.split(':').collect::<Vec<_>>();
         let driver_or_path = *splitted.get(0).context("no driver provided")?;
         let maybe_driver = Driver::from_str(driver_or_path);
@@ -181,17 +459,17 @@ mod tests {
             Tc {
                 input: "journald:/some/path",
                 should_error: false,
-                expected: Driver::Journald.into(),
+                expected: Driver::Journald(ContainerFields::default()).into(),
             },
             Tc {
                 input: "journald",
                 should_error: false,
-                expected: Driver::Journald.into(),
+                expected: Driver::Journald(ContainerFields::default()).into(),
             },
             Tc {
                 input: "journald:",
                 should_error: false,
-                expected: Driver::Journald.into(),
+                expected: Driver::Journald(ContainerFields::default()).into(),
             },
             Tc {
                 input: ":/some/path",
@@ -229,4 +507,119 @@ mod tests {
         }
         Ok(())
     }
+
+    fn new_container_logging() -> ContainerLogging {
+        ContainerLogging {
+            drivers: vec![],
+            files: vec![],
+            stdout_partial: vec![],
+            stderr_partial: vec![],
+            log_size_max: -1,
+            log_global_size_max: -1,
+            global_bytes_written: 0,
+            syslog_tag: None,
+        }
+    }
+
+    #[test]
+    fn container_fields_journald_metadata() -> Result<()> {
+        let mut fields = ContainerFields::default();
+        fields.set_id("CONTAINER_ID=abcdef012345".into());
+        fields.set_id_full("CONTAINER_ID_FULL=abcdef012345678".into());
+        fields.set_tag("CONTAINER_TAG=mytag".to_string().into());
+        fields.set_syslog_identifier("SYSLOG_IDENTIFIER=mytag".to_string().into());
+        assert_eq!(fields.id(), "CONTAINER_ID=abcdef012345");
+        assert_eq!(
+            fields.syslog_identifier().as_deref(),
+            Some("SYSLOG_IDENTIFIER=mytag")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn split_lines_full() {
+        let mut cl = new_container_logging();
+        let records = cl.split_lines(Stream::Stdout, b"hello\n");
+        assert_eq!(records, vec![(b"hello\n".to_vec(), true)]);
+        assert!(cl.stdout_partial.is_empty());
+    }
+
+    #[test]
+    fn split_lines_partial_then_full() {
+        let mut cl = new_container_logging();
+        assert_eq!(cl.split_lines(Stream::Stdout, b"hel"), vec![]);
+        assert_eq!(cl.stdout_partial, b"hel");
+
+        let records = cl.split_lines(Stream::Stdout, b"lo\n");
+        assert_eq!(records, vec![(b"hello\n".to_vec(), true)]);
+        assert!(cl.stdout_partial.is_empty());
+    }
+
+    #[test]
+    fn split_lines_keeps_streams_separate() {
+        let mut cl = new_container_logging();
+        cl.split_lines(Stream::Stdout, b"out");
+        cl.split_lines(Stream::Stderr, b"err");
+        assert_eq!(cl.stdout_partial, b"out");
+        assert_eq!(cl.stderr_partial, b"err");
+    }
+
+    #[test]
+    fn split_lines_forces_partial_record_when_buffer_too_large() {
+        let mut cl = new_container_logging();
+        let chunk = vec![b'a'; MAX_PARTIAL_LINE_LEN];
+        let records = cl.split_lines(Stream::Stdout, &chunk);
+        assert_eq!(records, vec![(chunk, false)]);
+        assert!(cl.stdout_partial.is_empty());
+    }
+
+    fn open_test_log_file(name: &str) -> Result<LogFile> {
+        let path = std::env::temp_dir().join(name);
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .write(true)
+            .open(&path)
+            .context("open test log file")?;
+        Ok(LogFile {
+            path,
+            file,
+            bytes_written: 0,
+        })
+    }
+
+    #[test]
+    fn write_rotates_file_once_size_max_exceeded() -> Result<()> {
+        let log_file = open_test_log_file("conmon-test-log-rotate.log")?;
+        let path = log_file.path.clone();
+        let mut cl = new_container_logging();
+        cl.files.push(log_file);
+        cl.log_size_max = 5;
+
+        cl.write(Stream::Stdout, b"hello\n")?;
+        cl.write(Stream::Stdout, b"world\n")?;
+
+        let contents = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path).ok();
+        assert!(!contents.contains("hello"));
+        assert!(contents.contains("world"));
+        Ok(())
+    }
+
+    #[test]
+    fn write_drops_output_once_global_cap_reached() -> Result<()> {
+        let log_file = open_test_log_file("conmon-test-log-global-cap.log")?;
+        let path = log_file.path.clone();
+        let mut cl = new_container_logging();
+        cl.files.push(log_file);
+        cl.log_global_size_max = 1;
+        cl.global_bytes_written = 1;
+
+        cl.write(Stream::Stdout, b"hello\n")?;
+
+        let contents = std::fs::read_to_string(&path)?;
+        std::fs::remove_file(&path).ok();
+        assert!(contents.is_empty());
+        Ok(())
+    }
 }
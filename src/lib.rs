@@ -4,8 +4,13 @@
 
 mod config;
 mod container_logging;
+mod runtime;
+mod seccomp;
 
-use crate::{config::Config, container_logging::ContainerLogging};
+use crate::{
+    config::Config,
+    container_logging::{ContainerLogging, Driver, Stream},
+};
 use anyhow::{bail, Context, Result};
 use derive_builder::Builder;
 use env_logger::fmt::Color;
@@ -13,14 +18,24 @@ use getset::{Getters, MutGetters};
 use log::{debug, warn, LevelFilter};
 use nix::{
     fcntl::{fcntl, FcntlArg, FdFlag, OFlag},
+    sys::epoll::{
+        epoll_create1, epoll_ctl, epoll_wait, EpollCreateFlags, EpollEvent, EpollFlags, EpollOp,
+    },
     sys::signal::{signal, SigHandler, Signal},
-    unistd::{close, dup2, fork, pipe2, read, setsid, ForkResult},
+    sys::socket::{
+        accept, bind, cmsg_space, listen, recvmsg, socket, AddressFamily, ControlMessageOwned,
+        MsgFlags, SockFlag, SockType, UnixAddr,
+    },
+    sys::wait::{waitpid, WaitStatus},
+    unistd::{close, dup2, fork, pipe2, read, setsid, write, ForkResult, Pid},
 };
 use std::{
     env,
+    ffi::CString,
     fs::{self, File, OpenOptions},
-    io::Write,
-    os::unix::io::AsRawFd,
+    io::{IoSliceMut, Write},
+    os::unix::io::{AsRawFd, RawFd},
+    path::Path,
     process::exit,
     ptr,
 };
@@ -29,6 +44,27 @@ const START_PIPE_ENV_KEY: &str = "_OCI_STARTPIPE";
 const SYNC_PIPE_ENV_KEY: &str = "_OCI_SYNCPIPE";
 const ATTACH_PIPE_ENV_KEY: &str = "_OCI_ATTACHPIPE";
 
+/// A single pluggable output for conmon's own diagnostic log messages, alongside the default
+/// `env_logger` stderr target.
+struct LogConfig {
+    /// Whether this target is enabled.
+    enabled: bool,
+
+    /// Formats the rendered log line for this target given the record it came from. `new` sets it
+    /// to pass the buffer through unchanged; no option or config value replaces that formatter.
+    formatter: Box<dyn Fn(&[u8], &log::Record) -> Vec<u8> + Send + Sync>,
+}
+
+impl LogConfig {
+    /// Create a new target with the default formatter.
+    fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            formatter: Box::new(|buf, _record| buf.to_vec()),
+        }
+    }
+}
+
 #[derive(Builder, Debug, Default, Getters, MutGetters)]
 #[builder(default, pattern = "owned", setter(into))]
 /// Conmon is the main structure to run the OCI container monitor.
@@ -47,12 +83,15 @@ impl Conmon {
         self.config_mut().validate().context("validate config")?;
         Self::unset_locale();
 
-        let _container_logging = ContainerLogging::new(
-            self.config().log_path(),
+        let mut container_logging = ContainerLogging::new(
+            self.config().log_drivers().context("parse log drivers")?,
             self.config().cuuid().as_ref(),
             self.config().name().as_ref(),
             self.config().log_tag().as_ref(),
-        );
+            self.config().log_size_max(),
+            self.config().log_global_size_max(),
+        )
+        .context("create container logging")?;
 
         if let Err(e) = Self::set_oom("-1000") {
             warn!("Unable to adjust oom score: {}", e)
@@ -121,44 +160,67 @@ impl Conmon {
         let mut workerfd_stderr = -1;
         let mut mainfd_stdin = -1;
         let mut mainfd_stdout = -1;
+        let mut mainfd_stderr = -1;
 
         if self.config().terminal() {
-            // setup_console_socket
-            unimplemented!("console socket setup is not implemented yet")
+            let master_fd =
+                Self::setup_console_socket(self.config().cid(), self.config().socket_dir_path())
+                    .context("set up console socket")?;
+            mainfd_stdout = master_fd;
+            mainfd_stdin = master_fd;
         } else {
-            // Create a "fake" main fd so that we can use the same epoll code in both cases. The
-            // workerfd_*s will be closed after we dup over everything. We use pipes here because
-            // open(/dev/std{out,err}) will fail if we used anything else (and it wouldn't be a
-            // good idea to create a new pty pair in the host).
+            // Create a "fake" main fd so that we can use the same epoll code in both cases. We
+            // use pipes here because open(/dev/std{out,err}) will fail if we used anything else
+            // (and it wouldn't be a good idea to create a new pty pair in the host).
             if self.config().stdin() {
                 let stdin = pipe2(OFlag::O_CLOEXEC)?;
                 mainfd_stdin = stdin.0;
                 workerfd_stdin = stdin.1;
-
-                if unsafe {
-                    glib_sys::g_unix_set_fd_nonblocking(
-                        mainfd_stdin,
-                        glib_sys::GTRUE,
-                        ptr::null_mut(),
-                    )
-                } == glib_sys::GFALSE
-                {
-                    warn!("Failed to set mainfd_stdin to non blocking")
-                }
+                Self::set_nonblocking(mainfd_stdin);
             }
 
             let stdout = pipe2(OFlag::O_CLOEXEC)?;
             mainfd_stdout = stdout.0;
             workerfd_stdout = stdout.1;
 
+            let stderr = pipe2(OFlag::O_CLOEXEC)?;
+            mainfd_stderr = stderr.0;
+            workerfd_stderr = stderr.1;
+
             // Now that we've set mainfd_stdout, we can register the ctrl_winsz_cb if we didn't set
             // it here, we'd risk attempting to run ioctl on a negative fd, and fail to resize the
             // window
+
+            // The worker ends are meant to become the container process's stdio once it is
+            // forked, but nothing in this tree dups them over yet. Close them here rather than
+            // leaking them for the life of conmon: holding them open would stop the main ends
+            // from ever observing EOF, so `run_io_pump` would never see these streams close.
+            for fd in [workerfd_stdin, workerfd_stdout, workerfd_stderr] {
+                if fd >= 0 {
+                    close(fd).ok();
+                }
+            }
         }
 
+        Self::run_io_pump(
+            &mut container_logging,
+            mainfd_stdout,
+            mainfd_stderr,
+            self.read_container_pid(),
+            sync_pipe_fd,
+        )
+        .context("run io pump")?;
+
         Ok(())
     }
 
+    /// Best-effort read of the container's pid from the pidfile the OCI runtime writes it to,
+    /// used to supervise the container process via a pidfd instead of polling for its death.
+    fn read_container_pid(&self) -> Option<i32> {
+        let path = self.config().container_pidfile().as_ref()?;
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
     /// Initialize the logger and set the verbosity to the provided level.
     fn init_logging(&self) -> Result<()> {
         // Set the logging verbosity via the env
@@ -169,12 +231,24 @@ impl Conmon {
         // [YYYY-MM-DDTHH:MM:SS:MMMZ LEVEL crate::module file:LINE] MSGâ€¦
         // The file and line will be only printed when running with debug or trace level.
         let log_level = self.config.log_level();
+
+        // The syslog target is enabled alongside the stderr/env_logger target below, so
+        // operators running without a writable log path still capture conmon's own diagnostics.
+        let syslog = LogConfig::new(self.config().syslog());
+        if syslog.enabled {
+            let ident = CString::new("conmon").context("build syslog identifier")?;
+            unsafe {
+                libc::openlog(ident.as_ptr(), libc::LOG_CONS | libc::LOG_PID, libc::LOG_USER)
+            };
+            // Kept alive for the process lifetime since `openlog` retains a pointer to it.
+            std::mem::forget(ident);
+        }
+
         env_logger::builder()
             .format(move |buf, r| {
                 let mut style = buf.style();
                 style.set_color(Color::Black).set_intense(true);
-                writeln!(
-                    buf,
+                let line = format!(
                     "{}{} {:<5} {}{}{} {}",
                     style.value("["),
                     buf.timestamp_millis(),
@@ -186,12 +260,39 @@ impl Conmon {
                     },
                     style.value("]"),
                     r.args()
-                )
+                );
+
+                if syslog.enabled {
+                    Self::write_to_syslog(r.level(), &(syslog.formatter)(line.as_bytes(), r));
+                }
+
+                writeln!(buf, "{}", line)
             })
             .try_init()
             .context("init env logger")
     }
 
+    /// Submit a single rendered log line to the system logger, mapping the log level to a
+    /// syslog priority.
+    fn write_to_syslog(level: log::Level, message: &[u8]) {
+        let priority = match level {
+            log::Level::Error => libc::LOG_ERR,
+            log::Level::Warn => libc::LOG_WARNING,
+            log::Level::Info => libc::LOG_INFO,
+            log::Level::Debug | log::Level::Trace => libc::LOG_DEBUG,
+        };
+        let message = String::from_utf8_lossy(message);
+        if let Ok(c_message) = CString::new(message.trim_end().as_bytes()) {
+            unsafe {
+                libc::syslog(
+                    priority,
+                    b"%s\0".as_ptr() as *const libc::c_char,
+                    c_message.as_ptr(),
+                )
+            };
+        }
+    }
+
     /// Unset the locale for the current process.
     fn unset_locale() {
         unsafe { libc::setlocale(libc::LC_ALL, "".as_ptr() as *const i8) };
@@ -223,8 +324,275 @@ impl Conmon {
         fcntl(value, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)).context("make CLOEXEC")
     }
 
+    /// Set up the console socket for a container requesting a pseudo-TTY: listen on a generated
+    /// UNIX socket, accept the single connection made by the OCI runtime, and receive the pty
+    /// master file descriptor sent as `SCM_RIGHTS` ancillary data.
+    fn setup_console_socket(cid: &str, socket_dir: &Path) -> Result<RawFd> {
+        let socket_path = socket_dir.join(format!("console-{}.sock", cid));
+        let _ = fs::remove_file(&socket_path);
+
+        let listen_fd = socket(
+            AddressFamily::Unix,
+            SockType::Stream,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )
+        .context("create console socket")?;
+        let addr = UnixAddr::new(&socket_path).context("build console socket address")?;
+        bind(listen_fd, &addr).context("bind console socket")?;
+        listen(listen_fd, 1).context("listen on console socket")?;
+
+        debug!(
+            "Waiting for console socket connection on {}",
+            socket_path.display()
+        );
+        let conn_fd = accept(listen_fd).context("accept console socket connection")?;
+        close(listen_fd).context("close console socket listener")?;
+
+        let master_fd = Self::recv_fd(conn_fd).context("receive console master fd")?;
+        close(conn_fd).context("close console socket connection")?;
+
+        fcntl(master_fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+            .context("set CLOEXEC on console master fd")?;
+        Ok(master_fd)
+    }
+
+    /// Receive a single file descriptor sent as `SCM_RIGHTS` ancillary data on `fd`, retrying on
+    /// short or truncated control messages.
+    fn recv_fd(fd: RawFd) -> Result<RawFd> {
+        let mut data_buf = [0u8; 128];
+        loop {
+            let mut iov = [IoSliceMut::new(&mut data_buf)];
+            let mut cmsg_buffer = cmsg_space!(RawFd);
+            let msg = recvmsg::<()>(fd, &mut iov, Some(&mut cmsg_buffer), MsgFlags::empty())
+                .context("recvmsg for console master fd")?;
+
+            if msg.bytes == 0 {
+                bail!("peer closed the console socket before sending a file descriptor");
+            }
+
+            for cmsg in msg.cmsgs() {
+                if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                    if let Some(received_fd) = fds.first() {
+                        return Ok(*received_fd);
+                    }
+                }
+            }
+
+            warn!("Received console socket message without a file descriptor, retrying");
+        }
+    }
+
+    /// Set a file descriptor to non-blocking mode, logging a warning on failure rather than
+    /// treating it as fatal.
+    fn set_nonblocking(fd: RawFd) {
+        if unsafe { glib_sys::g_unix_set_fd_nonblocking(fd, glib_sys::GTRUE, ptr::null_mut()) }
+            == glib_sys::GFALSE
+        {
+            warn!("Failed to set fd {} to non blocking", fd);
+        }
+    }
+
+    /// Run the central event loop that streams container stdout/stderr into the log sink until
+    /// every registered stream has closed.
+    ///
+    /// Each readable fd is drained in fixed-size reads; a zero-length read or `EPOLLHUP`
+    /// deregisters and closes that stream, and the loop returns once none remain open. Errors
+    /// writing to the log are demoted to warnings so a single write failure cannot bring down the
+    /// whole container.
+    ///
+    /// This does not yet pump `mainfd_stdin` or fan output out to attached clients: this tree has
+    /// no attach-socket subsystem (listener, client registry) to source stdin from or broadcast
+    /// to, so there is nothing to register for either side of that path yet.
+    ///
+    /// If `container_pid` is known, its pidfd is registered in the same epoll set: once it
+    /// becomes readable the container has exited, and a single targeted `waitpid` harvests the
+    /// exact exit status/signal and forwards it over `sync_pipe_fd`. Supervision silently falls
+    /// back to the `reap_children` `WNOHANG` sweep on kernels without `pidfd_open`.
+    fn run_io_pump(
+        container_logging: &mut ContainerLogging,
+        mainfd_stdout: RawFd,
+        mainfd_stderr: RawFd,
+        container_pid: Option<i32>,
+        sync_pipe_fd: RawFd,
+    ) -> Result<()> {
+        const BUF_SIZE: usize = 8192;
+
+        let epoll_fd = epoll_create1(EpollCreateFlags::EPOLL_CLOEXEC)
+            .context("create epoll instance for io pump")?;
+
+        let mut open_fds = vec![];
+        for (fd, stream) in [
+            (mainfd_stdout, Stream::Stdout),
+            (mainfd_stderr, Stream::Stderr),
+        ] {
+            if fd < 0 {
+                continue;
+            }
+            Self::set_nonblocking(fd);
+            let mut event = EpollEvent::new(EpollFlags::EPOLLIN, fd as u64);
+            epoll_ctl(epoll_fd, EpollOp::EpollCtlAdd, fd, Some(&mut event))
+                .context("register fd with epoll")?;
+            open_fds.push((fd, stream));
+        }
+
+        let pidfd = container_pid.and_then(Self::open_pidfd);
+        if let Some(fd) = pidfd {
+            let mut event = EpollEvent::new(EpollFlags::EPOLLIN, fd as u64);
+            epoll_ctl(epoll_fd, EpollOp::EpollCtlAdd, fd, Some(&mut event))
+                .context("register pidfd with epoll")?;
+        } else if container_pid.is_some() {
+            debug!("pidfd_open unsupported, falling back to the waitpid(WNOHANG) sweep at exit");
+        }
+
+        let mut buf = [0u8; BUF_SIZE];
+        while !open_fds.is_empty() {
+            let mut events = [EpollEvent::empty(); 16];
+            let n = match epoll_wait(epoll_fd, &mut events, -1) {
+                Ok(n) => n,
+                Err(nix::Error::EINTR) => continue,
+                Err(e) => return Err(e).context("epoll_wait"),
+            };
+
+            for event in &events[..n] {
+                let fd = event.data() as RawFd;
+
+                if Some(fd) == pidfd {
+                    if let Some(pid) = container_pid {
+                        Self::harvest_exit_status(pid, sync_pipe_fd);
+                    }
+                    epoll_ctl(epoll_fd, EpollOp::EpollCtlDel, fd, None).ok();
+                    close(fd).ok();
+                    continue;
+                }
+
+                let stream = match open_fds.iter().find(|(f, _)| *f == fd) {
+                    Some((_, stream)) => *stream,
+                    None => continue,
+                };
+
+                let mut eof = event.events().contains(EpollFlags::EPOLLHUP);
+                if event.events().contains(EpollFlags::EPOLLIN) {
+                    loop {
+                        match read(fd, &mut buf) {
+                            Ok(0) => {
+                                eof = true;
+                                break;
+                            }
+                            Ok(n) => {
+                                if let Err(e) = container_logging.write(stream, &buf[..n]) {
+                                    warn!("Failed to write container output to log: {}", e);
+                                }
+                            }
+                            Err(nix::Error::EAGAIN) => break,
+                            Err(nix::Error::EINTR) => continue,
+                            Err(e) => {
+                                warn!("Failed to read from {:?}: {}", stream, e);
+                                eof = true;
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                if eof {
+                    epoll_ctl(epoll_fd, EpollOp::EpollCtlDel, fd, None).ok();
+                    close(fd).ok();
+                    open_fds.retain(|(f, _)| *f != fd);
+                }
+            }
+        }
+
+        close(epoll_fd).context("close epoll instance")?;
+        Ok(())
+    }
+
+    /// Open a pidfd for `pid` via `pidfd_open(2)`, returning `None` on kernels that don't support
+    /// it (pre-5.3) so callers can fall back to the `WNOHANG` sweep.
+    fn open_pidfd(pid: i32) -> Option<RawFd> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if fd < 0 {
+            None
+        } else {
+            Some(fd as RawFd)
+        }
+    }
+
+    /// Harvest the exact exit status/termination signal of `pid` with a single targeted
+    /// `waitpid`, called once its pidfd has signalled readability, and forward it over
+    /// `sync_pipe_fd` so the caller learns the precise outcome instead of a generic reap.
+    fn harvest_exit_status(pid: i32, sync_pipe_fd: RawFd) {
+        let status = match waitpid(Pid::from_raw(pid), None) {
+            Ok(status) => status,
+            Err(e) => {
+                warn!("Failed to reap container process {}: {}", pid, e);
+                return;
+            }
+        };
+
+        let (exit_code, signal) = match status {
+            WaitStatus::Exited(_, code) => (code, 0),
+            WaitStatus::Signaled(_, sig, _) => (-1, sig as i32),
+            _ => return,
+        };
+
+        if sync_pipe_fd >= 0 {
+            let message = format!("{} {}\n", exit_code, signal);
+            if let Err(e) = write(sync_pipe_fd, message.as_bytes()) {
+                warn!("Failed to write exit status to sync pipe: {}", e);
+            }
+        }
+    }
+
+    /// Fallback reaper for kernels without `pidfd_open` support, where the container process's
+    /// death cannot be observed through the `run_io_pump` epoll set.
     extern "C" fn reap_children() {
         // We need to reap any zombies (from an OCI runtime that errored) before exiting
         unsafe { while libc::waitpid(-1, ptr::null_mut(), libc::WNOHANG) > 0 {} };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_io_pump_drains_stdout_and_stderr_until_closed() -> Result<()> {
+        let path = env::temp_dir().join("conmon-test-run-io-pump.log");
+        let mut container_logging = ContainerLogging::new(
+            vec![Driver::K8sFile(path.clone())],
+            None::<&str>,
+            None::<&str>,
+            None::<&str>,
+            -1,
+            -1,
+        )?;
+
+        let (stdout_r, stdout_w) = pipe2(OFlag::O_CLOEXEC)?;
+        let (stderr_r, stderr_w) = pipe2(OFlag::O_CLOEXEC)?;
+
+        write(stdout_w, b"hello\n")?;
+        write(stderr_w, b"world\n")?;
+        close(stdout_w)?;
+        close(stderr_w)?;
+
+        Conmon::run_io_pump(&mut container_logging, stdout_r, stderr_r, None, -1)?;
+
+        let contents = fs::read_to_string(&path)?;
+        fs::remove_file(&path).ok();
+        assert!(contents.contains("hello"));
+        assert!(contents.contains("world"));
+        Ok(())
+    }
+
+    #[test]
+    fn recv_fd_fails_instead_of_busy_looping_when_peer_closes_without_sending_one() {
+        use std::os::unix::net::UnixStream;
+
+        let (ours, theirs) = UnixStream::pair().expect("create socket pair");
+        drop(theirs);
+
+        let err = Conmon::recv_fd(ours.as_raw_fd()).expect_err("expected EOF to be an error");
+        assert!(err.to_string().contains("closed"));
+    }
+}
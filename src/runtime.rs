@@ -0,0 +1,300 @@
+//! A typed client for driving the configured OCI runtime binary.
+
+use anyhow::{bail, Context, Result};
+use getset::{CopyGetters, Getters};
+use log::debug;
+use nix::{
+    fcntl::OFlag,
+    sys::wait::{waitpid, WaitStatus},
+    unistd::{close, dup2, execvp, fork, pipe2, read, write, ForkResult},
+};
+use std::{
+    ffi::CString,
+    os::unix::io::RawFd,
+    path::{Path, PathBuf},
+};
+
+/// Footer written after the raw errno bytes on the sentinel pipe to mark a failed `execvp`, so
+/// the parent can tell it apart from a truncated read.
+const EXEC_ERROR_FOOTER: &[u8; 4] = b"NOEX";
+
+#[derive(Clone, Debug)]
+/// Runtime is a typed client for invoking the OCI runtime binary configured via `--runtime`,
+/// `--runtime-arg` and `--runtime-opt`, instead of callers hand-assembling an argv themselves.
+pub struct Runtime {
+    /// Path to the OCI runtime binary.
+    path: PathBuf,
+
+    /// Additional arguments passed before the subcommand on every invocation.
+    args: Vec<String>,
+
+    /// Additional options passed to the `exec`/`restore` commands.
+    opts: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, CopyGetters, Getters)]
+/// The outcome of a single OCI runtime invocation.
+pub struct RuntimeStatus {
+    #[getset(get_copy = "pub")]
+    /// The process exit code, present if the runtime exited normally.
+    exit_code: Option<i32>,
+
+    #[getset(get_copy = "pub")]
+    /// The signal that terminated the runtime process, if any.
+    signal: Option<i32>,
+
+    #[getset(get = "pub")]
+    /// The runtime's stderr output, captured regardless of success so callers can log it.
+    stderr: String,
+}
+
+impl Runtime {
+    /// Create a new runtime client from the individual `Config` fields.
+    pub fn new<T: Into<PathBuf>>(path: T, args: Vec<String>, opts: Vec<String>) -> Self {
+        Self {
+            path: path.into(),
+            args,
+            opts,
+        }
+    }
+
+    /// Create a container from the bundle at `bundle_path`, identified by `cid`.
+    pub fn create(&self, cid: &str, bundle_path: &Path, pid_file: &Path) -> Result<RuntimeStatus> {
+        self.run(
+            cid,
+            vec![
+                "create".into(),
+                "--bundle".into(),
+                bundle_path.display().to_string(),
+                "--pid-file".into(),
+                pid_file.display().to_string(),
+                cid.into(),
+            ],
+        )
+    }
+
+    /// Start the previously created container identified by `cid`.
+    pub fn start(&self, cid: &str) -> Result<RuntimeStatus> {
+        self.run(cid, vec!["start".into(), cid.into()])
+    }
+
+    /// Exec the process spec at `process_spec_path` into the running container `cid`.
+    pub fn exec(
+        &self,
+        cid: &str,
+        process_spec_path: &Path,
+        pid_file: &Path,
+    ) -> Result<RuntimeStatus> {
+        let mut sub_args = vec![
+            "exec".to_string(),
+            "--process".into(),
+            process_spec_path.display().to_string(),
+            "--pid-file".into(),
+            pid_file.display().to_string(),
+        ];
+        sub_args.extend(self.opts.clone());
+        sub_args.push(cid.into());
+        self.run(cid, sub_args)
+    }
+
+    /// Restore the container identified by `cid` from the checkpoint at `image_path`.
+    pub fn restore(&self, cid: &str, image_path: &Path, pid_file: &Path) -> Result<RuntimeStatus> {
+        let mut sub_args = vec![
+            "restore".to_string(),
+            "--pid-file".into(),
+            pid_file.display().to_string(),
+            "--image-path".into(),
+            image_path.display().to_string(),
+        ];
+        sub_args.extend(self.opts.clone());
+        sub_args.push(cid.into());
+        self.run(cid, sub_args)
+    }
+
+    /// Send `signal` to the container process identified by `cid`.
+    pub fn kill(&self, cid: &str, signal: &str) -> Result<RuntimeStatus> {
+        self.run(cid, vec!["kill".into(), cid.into(), signal.into()])
+    }
+
+    /// Delete the container identified by `cid`.
+    pub fn delete(&self, cid: &str) -> Result<RuntimeStatus> {
+        self.run(cid, vec!["delete".into(), "--force".into(), cid.into()])
+    }
+
+    /// Spawn the runtime binary with `self.args` followed by `sub_args`, capturing its output and
+    /// turning a non-zero exit (or a failed `execvp`) into an `anyhow` error keyed by `cid`.
+    ///
+    /// The child reports a failed `execvp` back to the parent over a `O_CLOEXEC` sentinel pipe:
+    /// a successful `execvp` closes the write end as a side effect, so the parent's read returns
+    /// EOF, while a failed one writes the raw errno followed by `EXEC_ERROR_FOOTER` before
+    /// exiting, letting the parent surface the precise reason instead of a generic wait failure.
+    fn run(&self, cid: &str, sub_args: Vec<String>) -> Result<RuntimeStatus> {
+        let mut full_args = self.args.clone();
+        full_args.extend(sub_args);
+        debug!(
+            "Running OCI runtime {} with args {:?} for container {}",
+            self.path.display(),
+            full_args,
+            cid
+        );
+
+        let (sentinel_r, sentinel_w) =
+            pipe2(OFlag::O_CLOEXEC).context("create exec sentinel pipe")?;
+        let (stdout_r, stdout_w) = pipe2(OFlag::O_CLOEXEC).context("create runtime stdout pipe")?;
+        let (stderr_r, stderr_w) = pipe2(OFlag::O_CLOEXEC).context("create runtime stderr pipe")?;
+
+        match unsafe { fork() }.with_context(|| format!("fork OCI runtime for {}", cid))? {
+            ForkResult::Child => {
+                let _ = close(sentinel_r);
+                let _ = close(stdout_r);
+                let _ = close(stderr_r);
+                let _ = dup2(stdout_w, libc::STDOUT_FILENO);
+                let _ = dup2(stderr_w, libc::STDERR_FILENO);
+
+                let path =
+                    CString::new(self.path.to_string_lossy().into_owned()).unwrap_or_default();
+                let mut argv = vec![path.clone()];
+                argv.extend(full_args.iter().filter_map(|a| CString::new(a.as_str()).ok()));
+
+                // On success `execvp` never returns, and the kernel closes `sentinel_w` on our
+                // behalf because it was opened `O_CLOEXEC`.
+                let errno = execvp(&path, &argv).unwrap_err() as i32;
+                let mut payload = errno.to_ne_bytes().to_vec();
+                payload.extend_from_slice(EXEC_ERROR_FOOTER);
+                let _ = write(sentinel_w, &payload);
+                std::process::exit(127);
+            }
+            ForkResult::Parent { child } => {
+                close(sentinel_w).context("close sentinel pipe write end")?;
+                close(stdout_w).context("close runtime stdout pipe write end")?;
+                close(stderr_w).context("close runtime stderr pipe write end")?;
+
+                let exec_errno = Self::read_sentinel(sentinel_r)
+                    .context("read exec sentinel pipe")
+                    .map(|errno| {
+                        close(sentinel_r).ok();
+                        errno
+                    })?;
+                // The runtime's stdout isn't surfaced to callers today, but it must still be
+                // drained so the child never blocks writing to a full pipe. stderr is drained on
+                // its own thread so that neither pipe can fill up while the other one is read.
+                let stderr_reader = std::thread::spawn(move || Self::drain(stderr_r));
+                let _stdout = Self::drain(stdout_r);
+                let stderr = stderr_reader.join().unwrap_or_default();
+                close(stdout_r).ok();
+                close(stderr_r).ok();
+
+                if let Some(errno) = exec_errno {
+                    waitpid(child, None).ok();
+                    bail!(
+                        "failed to exec runtime for container {}: {}",
+                        cid,
+                        nix::Error::from_i32(errno)
+                    );
+                }
+
+                let status = waitpid(child, None)
+                    .with_context(|| format!("wait for OCI runtime for container {}", cid))?;
+                let (exit_code, signal) = match status {
+                    WaitStatus::Exited(_, code) => (Some(code), None),
+                    WaitStatus::Signaled(_, sig, _) => (None, Some(sig as i32)),
+                    _ => (None, None),
+                };
+
+                if exit_code != Some(0) {
+                    bail!(
+                        "OCI runtime failed for container {}: {}",
+                        cid,
+                        if stderr.is_empty() {
+                            format!("{:?}", status)
+                        } else {
+                            stderr.clone()
+                        }
+                    );
+                }
+
+                Ok(RuntimeStatus {
+                    exit_code,
+                    signal,
+                    stderr,
+                })
+            }
+        }
+    }
+
+    /// Read the exec sentinel pipe until either EOF (the runtime launched successfully) or the
+    /// full errno+footer payload has been seen, guarding against partial reads.
+    fn read_sentinel(fd: RawFd) -> Result<Option<i32>> {
+        let mut payload = Vec::with_capacity(EXEC_ERROR_FOOTER.len() + 4);
+        let mut buf = [0u8; 8];
+        loop {
+            let n = match read(fd, &mut buf) {
+                Ok(n) => n,
+                Err(nix::Error::EINTR) => continue,
+                Err(e) => return Err(e).context("read exec sentinel pipe"),
+            };
+            if n == 0 {
+                break;
+            }
+            payload.extend_from_slice(&buf[..n]);
+            if payload.len() >= 4 + EXEC_ERROR_FOOTER.len() {
+                break;
+            }
+        }
+
+        if payload.is_empty() {
+            return Ok(None);
+        }
+        if payload.len() < 4 + EXEC_ERROR_FOOTER.len() || &payload[4..] != EXEC_ERROR_FOOTER {
+            bail!("truncated exec sentinel pipe payload");
+        }
+        let mut errno_bytes = [0u8; 4];
+        errno_bytes.copy_from_slice(&payload[..4]);
+        Ok(Some(i32::from_ne_bytes(errno_bytes)))
+    }
+
+    /// Read `fd` to EOF and collect it into a `String`, used to capture the runtime's stdout and
+    /// stderr pipes after it has exited.
+    fn drain(fd: RawFd) -> String {
+        let mut out = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            match read(fd, &mut buf) {
+                Ok(0) => break,
+                Ok(n) => out.extend_from_slice(&buf[..n]),
+                Err(nix::Error::EINTR) => continue,
+                Err(_) => break,
+            }
+        }
+        String::from_utf8_lossy(&out).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_succeeds_and_captures_stderr() -> Result<()> {
+        let runtime = Runtime::new("/bin/sh", vec![], vec![]);
+        let status = runtime.run(
+            "test-cid",
+            vec!["-c".into(), "echo captured >&2; exit 0".into()],
+        )?;
+        assert_eq!(status.exit_code(), Some(0));
+        assert_eq!(status.signal(), None);
+        assert_eq!(status.stderr(), "captured\n");
+        Ok(())
+    }
+
+    #[test]
+    fn run_fails_with_context_on_non_zero_exit() {
+        let runtime = Runtime::new("/bin/sh", vec![], vec![]);
+        let err = runtime
+            .run("test-cid", vec!["-c".into(), "echo boom >&2; exit 1".into()])
+            .expect_err("expected runtime failure");
+        let message = format!("{:#}", err);
+        assert!(message.contains("test-cid"));
+        assert!(message.contains("boom"));
+    }
+}
@@ -0,0 +1,268 @@
+//! Seccomp user notification handling, driven by `--seccomp-notify-socket` and
+//! `--seccomp-notify-plugins`.
+
+use anyhow::{bail, Context, Result};
+use libloading::{Library, Symbol};
+use log::{debug, warn};
+use nix::{
+    cmsg_space,
+    sys::socket::{recvmsg, ControlMessageOwned, MsgFlags},
+    unistd::close,
+};
+use std::{
+    io::IoSliceMut,
+    os::unix::{
+        io::RawFd,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+};
+
+// SECCOMP_IOC_MAGIC, from <linux/seccomp.h>.
+nix::ioctl_readwrite!(seccomp_notif_recv, b'!', 0, SeccompNotif);
+nix::ioctl_readwrite!(seccomp_notif_send, b'!', 1, SeccompNotifResp);
+
+/// Mirrors the kernel's `struct seccomp_data` from `<linux/seccomp.h>`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SeccompData {
+    /// The syscall number.
+    pub nr: i32,
+    /// The audit architecture of the calling process.
+    pub arch: u32,
+    /// The instruction pointer at the time of the syscall.
+    pub instruction_pointer: u64,
+    /// The raw syscall arguments.
+    pub args: [u64; 6],
+}
+
+/// Mirrors the kernel's `struct seccomp_notif` from `<linux/seccomp.h>`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SeccompNotif {
+    /// Unique notification ID, must be echoed back in the response.
+    pub id: u64,
+    /// PID of the process which triggered the notification.
+    pub pid: u32,
+    /// Reserved, currently unused.
+    pub flags: u32,
+    /// The syscall which triggered the notification.
+    pub data: SeccompData,
+}
+
+/// Mirrors the kernel's `struct seccomp_notif_resp` from `<linux/seccomp.h>`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SeccompNotifResp {
+    /// Must match the `id` of the `SeccompNotif` this responds to.
+    pub id: u64,
+    /// The value to return from the syscall when `error` is `0`.
+    pub val: i64,
+    /// A negative errno to fail the syscall with, or `0` to return `val` as its result.
+    pub error: i32,
+    /// Set to `SECCOMP_USER_NOTIF_FLAG_CONTINUE` to let the syscall proceed unmodified instead of
+    /// returning `val`/`error`.
+    pub flags: u32,
+}
+
+/// Tells the kernel to let the syscall proceed unmodified instead of using `val`/`error` as its
+/// result, from `<linux/seccomp.h>`.
+const SECCOMP_USER_NOTIF_FLAG_CONTINUE: u32 = 1;
+
+/// The verdict a plugin returns for a single intercepted syscall.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Verdict {
+    /// Let the syscall proceed unmodified.
+    Continue,
+    /// Emulate the syscall by failing it with the given errno.
+    Errno(i32),
+}
+
+/// A plugin that can judge intercepted syscalls for one or more containers.
+///
+/// Plugins are native shared libraries looked up by name and are expected to export a
+/// `conmon_seccomp_handle` symbol with the signature:
+/// `extern "C" fn(nr: i32, args: *const u64, errno_out: *mut i32) -> i32`, returning `0` to let
+/// the syscall proceed, `1` to fail it with the errno written to `errno_out`, or `-1` if this
+/// plugin does not want to judge the syscall, in which case the next plugin is tried.
+pub struct Plugin {
+    /// The plugin name, as given in `--seccomp-notify-plugins`.
+    name: String,
+
+    /// The loaded shared library, kept alive for the symbol's lifetime.
+    library: Library,
+}
+
+type HandleFn = unsafe extern "C" fn(nr: i32, args: *const u64, errno_out: *mut i32) -> i32;
+
+impl Plugin {
+    /// Load a plugin shared library by name, e.g. `name` of `libname.so`.
+    pub fn load(name: &str) -> Result<Self> {
+        let file_name = format!("lib{}.so", name);
+        let library = unsafe { Library::new(&file_name) }
+            .with_context(|| format!("load seccomp notify plugin {}", file_name))?;
+        Ok(Self {
+            name: name.into(),
+            library,
+        })
+    }
+
+    /// Ask this plugin to judge a single syscall, returning `None` if it declines to handle it.
+    pub fn handle(&self, data: &SeccompData) -> Result<Option<Verdict>> {
+        let handle: Symbol<HandleFn> = unsafe {
+            self.library
+                .get(b"conmon_seccomp_handle")
+                .with_context(|| format!("resolve symbol in plugin {}", self.name))?
+        };
+
+        let mut errno = 0;
+        let ret = unsafe { handle(data.nr, data.args.as_ptr(), &mut errno) };
+        Ok(match ret {
+            0 => Some(Verdict::Continue),
+            1 => Some(Verdict::Errno(errno)),
+            _ => None,
+        })
+    }
+}
+
+/// SeccompNotify listens on the configured UNIX socket, receives the seccomp notification fd
+/// passed by the OCI runtime via `SCM_RIGHTS`, and dispatches intercepted syscalls to an ordered
+/// list of plugins.
+pub struct SeccompNotify {
+    /// The listening socket the OCI runtime connects to.
+    listener: UnixListener,
+
+    /// Plugins tried in order for every intercepted syscall.
+    plugins: Vec<Plugin>,
+}
+
+impl SeccompNotify {
+    /// Bind the listening socket at `socket_path` and load the comma-separated `plugins` list.
+    pub fn new(socket_path: &Path, plugins: &str) -> Result<Self> {
+        let listener = UnixListener::bind(socket_path)
+            .with_context(|| format!("bind seccomp notify socket {}", socket_path.display()))?;
+
+        let plugins = plugins
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(Plugin::load)
+            .collect::<Result<Vec<_>>>()
+            .context("load seccomp notify plugins")?;
+
+        Ok(Self { listener, plugins })
+    }
+
+    /// Accept a single connection from the OCI runtime and receive the seccomp notification fd
+    /// sent as ancillary data.
+    pub fn accept(&self) -> Result<RawFd> {
+        let (stream, _) = self.listener.accept().context("accept seccomp socket")?;
+        Self::recv_fd(&stream)
+    }
+
+    /// Receive a single file descriptor over `SCM_RIGHTS` on `stream`.
+    fn recv_fd(stream: &UnixStream) -> Result<RawFd> {
+        use std::os::unix::io::AsRawFd;
+
+        let mut buf = [0u8; 1];
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let mut cmsg_buffer = cmsg_space!(RawFd);
+
+        let msg = recvmsg::<()>(
+            stream.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg_buffer),
+            MsgFlags::empty(),
+        )
+        .context("recvmsg for seccomp notify fd")?;
+
+        for cmsg in msg.cmsgs() {
+            if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                let fd = *fds.first().context("no fd in SCM_RIGHTS message")?;
+                nix::fcntl::fcntl(
+                    fd,
+                    nix::fcntl::FcntlArg::F_SETFD(nix::fcntl::FdFlag::FD_CLOEXEC),
+                )
+                .context("set FD_CLOEXEC on seccomp notify fd")?;
+                return Ok(fd);
+            }
+        }
+        bail!("no SCM_RIGHTS control message received")
+    }
+
+    /// Run the receive/dispatch/respond loop for a single notification fd until it is closed.
+    pub fn run(&self, notify_fd: RawFd) -> Result<()> {
+        loop {
+            let mut notif = SeccompNotif::default();
+            match unsafe { seccomp_notif_recv(notify_fd, &mut notif) } {
+                Ok(_) => {}
+                Err(nix::Error::EINTR) => continue,
+                Err(e) => {
+                    debug!("Stopping seccomp notify loop: {}", e);
+                    break;
+                }
+            }
+
+            let verdict = self.dispatch(&notif.data)?;
+            let mut resp = Self::response_for(notif.id, verdict);
+
+            if let Err(e) = unsafe { seccomp_notif_send(notify_fd, &mut resp) } {
+                warn!("Failed to send seccomp notify response: {}", e);
+            }
+        }
+
+        close(notify_fd).context("close seccomp notify fd")?;
+        Ok(())
+    }
+
+    /// Ask every plugin in order until one returns a verdict, defaulting to letting the syscall
+    /// proceed unmodified if none of them do.
+    fn dispatch(&self, data: &SeccompData) -> Result<Verdict> {
+        for plugin in &self.plugins {
+            if let Some(verdict) = plugin.handle(data)? {
+                return Ok(verdict);
+            }
+        }
+        Ok(Verdict::Continue)
+    }
+
+    /// Build the `SeccompNotifResp` fields for `verdict`, responding to the notification `id`.
+    ///
+    /// `Verdict::Continue` must set `SECCOMP_USER_NOTIF_FLAG_CONTINUE` rather than leaving
+    /// `error` at `0`, since `error == 0` tells the kernel to return `val` as the syscall's
+    /// result instead of letting it execute.
+    fn response_for(id: u64, verdict: Verdict) -> SeccompNotifResp {
+        let mut resp = SeccompNotifResp {
+            id,
+            val: 0,
+            error: 0,
+            flags: 0,
+        };
+        match verdict {
+            Verdict::Continue => resp.flags = SECCOMP_USER_NOTIF_FLAG_CONTINUE,
+            Verdict::Errno(errno) => resp.error = -errno,
+        }
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_for_continue_sets_continue_flag_not_error() {
+        let resp = SeccompNotify::response_for(42, Verdict::Continue);
+        assert_eq!(resp.id, 42);
+        assert_eq!(resp.flags, SECCOMP_USER_NOTIF_FLAG_CONTINUE);
+        assert_eq!(resp.error, 0);
+    }
+
+    #[test]
+    fn response_for_errno_sets_negative_error_not_flag() {
+        let resp = SeccompNotify::response_for(7, Verdict::Errno(13));
+        assert_eq!(resp.id, 7);
+        assert_eq!(resp.error, -13);
+        assert_eq!(resp.flags, 0);
+    }
+}